@@ -1,7 +1,7 @@
 use anyhow::{bail, Result};
 use ron::de::from_reader;
 use serde::Deserialize;
-use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::env;
 use std::fmt::Write;
@@ -21,37 +21,239 @@ enum Contents {
     TimeOfDay,
 }
 
+impl Contents {
+    fn size(&self) -> u8 {
+        match self {
+            Contents::Byte => 1,
+            Contents::Word => 2,
+            Contents::Word24 => 3,
+            Contents::Word32 => 4,
+            Contents::Word40 => 5,
+            Contents::Word48 => 6,
+            Contents::Frequency => 8,
+            Contents::TimeOfDay => 11,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Field {
+    name: String,
+    bits: (u8, u8),
+    #[serde(default)]
+    values: Vec<(u64, String)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Register(u16, String, Contents, #[serde(default)] Vec<Field>);
+
+/// A single instance address of a module, optionally restricted to a subset
+/// of the device variants the enclosing module otherwise applies to (e.g. a
+/// second DPLL instance only present on larger parts in the family).
 #[derive(Debug, Deserialize)]
-struct Register(u16, String, Contents);
+#[serde(untagged)]
+enum Base {
+    Addr(u16),
+    Scoped {
+        addr: u16,
+        #[serde(default)]
+        devices: Vec<String>,
+    },
+}
+
+impl Base {
+    fn addr(&self) -> u16 {
+        match self {
+            Base::Addr(addr) => *addr,
+            Base::Scoped { addr, .. } => *addr,
+        }
+    }
+
+    /// The devices this base is present on, or `None` to inherit the
+    /// enclosing module's devices.
+    fn devices(&self) -> Option<&[String]> {
+        match self {
+            Base::Addr(_) => None,
+            Base::Scoped { devices, .. } => Some(devices),
+        }
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct Module {
-    base: Vec<u16>,
+    base: Vec<Base>,
+    /// The device variants this module is present on; empty means every
+    /// variant in the family.
+    #[serde(default)]
+    devices: Vec<String>,
     registers: Vec<Register>,
 }
 
+/// Whether `devices` (a module's or a base's device list) includes `device`,
+/// treating an empty list as "every device".
+fn applies_to(devices: &[String], device: &str) -> bool {
+    devices.is_empty() || devices.iter().any(|d| d == device)
+}
+
+/// Turns a register, module, or device name into a valid (and
+/// unique-enough) Rust identifier fragment.
+fn ident(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+
+    if ident.chars().next().map_or(true, |c| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+
+    ident
+}
+
 type Modules = HashMap<String, Module>;
 
-fn output_modules(modules: &Modules) -> Result<String> {
-    let mut s = String::new();
-    let mut sorted = BTreeMap::new();
+/// The emission order for `device`'s module array: every module with at
+/// least one base surviving for `device`, sorted by that device's own first
+/// surviving base address. A module's global first base (`base[0]`) may
+/// belong to a `Base::Scoped` entry for an entirely different device, so
+/// reusing the family-wide sort order here can hand a device's array to
+/// `output_module_array` in an order that isn't ascending for that device
+/// at all.
+fn sorted_for_device(modules: &Modules, device: &str) -> Vec<String> {
+    let mut sorted: Vec<(u16, String)> = modules
+        .iter()
+        .filter(|(_, module)| applies_to(&module.devices, device))
+        .filter_map(|(name, module)| {
+            module
+                .base
+                .iter()
+                .find(|base| applies_to(base.devices().unwrap_or(&module.devices), device))
+                .map(|base| (base.addr(), name.clone()))
+        })
+        .collect();
 
-    for (name, module) in modules {
-        sorted.insert(module.base[0], name.to_string());
+    sorted.sort();
+    sorted.into_iter().map(|(_, name)| name).collect()
+}
+
+/// Validates that `fields` don't overlap one another and all fit within a
+/// register whose contents are `width` bits wide, then emits a `static`
+/// holding them.
+fn output_fields(
+    decls: &mut String,
+    module: &str,
+    register: &str,
+    contents: &Contents,
+    width: u8,
+    fields: &[Field],
+) -> Result<String> {
+    if fields.is_empty() {
+        return Ok("&[]".to_string());
     }
 
-    writeln!(
-        &mut s,
-        r##"
-pub fn modules() -> &'static [Module<'static>] {{
-    &["##
-    )?;
+    // `Payload::field`/`with_field` extract bits out of `self.value()`,
+    // which for these two variants isn't the raw bit-packed contents:
+    // `Frequency` collapses to an `m / n` ratio, and `TimeOfDay` discards
+    // its sub-second bytes entirely. A field declared on either would
+    // silently read/write nonsense, so reject it at build time instead.
+    if matches!(contents, Contents::Frequency | Contents::TimeOfDay) {
+        bail!(
+            "{}.{} is {:?} and can't carry named fields",
+            module,
+            register,
+            contents
+        );
+    }
+
+    let mut seen: Vec<(u8, u8)> = Vec::new();
+
+    for f in fields {
+        let (lsb, msb) = f.bits;
+
+        if lsb > msb {
+            bail!("field {} of {}.{}: lsb > msb", f.name, module, register);
+        }
+
+        if msb >= width {
+            bail!(
+                "field {} of {}.{} exceeds register width ({} bits)",
+                f.name,
+                module,
+                register,
+                width
+            );
+        }
+
+        for (slsb, smsb) in &seen {
+            if lsb <= *smsb && *slsb <= msb {
+                bail!("field {} of {}.{} overlaps another field", f.name, module, register);
+            }
+        }
+
+        seen.push((lsb, msb));
+    }
+
+    let name = format!("FIELDS_{}_{}", ident(module), ident(register));
 
-    for (_, name) in &sorted {
+    write!(decls, "\nstatic {}: &[Field] = &[", name)?;
+
+    for f in fields {
+        let values = f
+            .values
+            .iter()
+            .map(|(v, name)| format!("(0x{:x}, \"{}\")", v, name))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        write!(
+            decls,
+            r##"
+    Field {{ name: "{}", lsb: {}, msb: {}, values: &[{}] }},"##,
+            f.name, f.bits.0, f.bits.1, values
+        )?;
+    }
+
+    writeln!(decls, "\n];")?;
+
+    Ok(name)
+}
+
+/// Emits a `Module<'static>` array literal (without the enclosing
+/// `pub fn`/`static` wrapper) for the modules in `sorted` order, keeping
+/// only bases that pass `keep_base`. A module with no surviving bases is
+/// omitted entirely, which shifts every later module's position in *this*
+/// call's array. The returned `ADDRESS_INDEX`-style entries use `module_idx`
+/// values from `sorted` itself (not from the position in this call's
+/// output), so they're only meaningful when `keep_base` never drops a
+/// module — i.e. for the unfiltered call that builds `modules()` and
+/// `ADDRESS_INDEX`. Per-device calls return entries that don't correspond to
+/// `modules_for(device)`'s actual indices and are discarded by the caller.
+fn output_module_array(
+    s: &mut String,
+    modules: &Modules,
+    sorted: &[String],
+    field_names: &HashMap<(String, String), String>,
+    keep_base: impl Fn(&Module, &Base) -> bool,
+) -> Result<Vec<(u16, u16, usize, usize, usize)>> {
+    let mut index = Vec::new();
+
+    writeln!(s, "&[")?;
+
+    for (module_idx, name) in sorted.iter().enumerate() {
         let module = modules.get(name).unwrap();
+        let bases: Vec<(usize, &Base)> = module
+            .base
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| keep_base(module, b))
+            .collect();
+
+        if bases.is_empty() {
+            continue;
+        }
 
         write!(
-            &mut s,
+            s,
             r##"
         Module {{
             name: "{}",
@@ -59,35 +261,190 @@ pub fn modules() -> &'static [Module<'static>] {{
             name,
         )?;
 
-        for b in &module.base {
-            write!(&mut s, "0x{:x}, ", b)?;
+        for (_, base) in &bases {
+            write!(s, "0x{:x}, ", base.addr())?;
         }
 
         writeln!(
-            &mut s,
+            s,
             r##"],
             registers: &["##,
         )?;
 
         for r in &module.registers {
+            let fields = field_names
+                .get(&(name.clone(), r.1.clone()))
+                .expect("fields precomputed for every register");
+
             writeln!(
-                &mut s,
+                s,
                 r##"
                 Register {{
                     name: "{}",
                     offset: 0x{:x},
                     contents: Contents::{:?},
+                    fields: {},
                 }},"##,
-                r.1, r.0, r.2
+                r.1, r.0, r.2, fields
             )?;
         }
 
-        writeln!(&mut s, "            ]\n        }},")?;
+        writeln!(s, "            ]\n        }},")?;
+
+        for (base_idx, base) in &bases {
+            for (reg_idx, r) in module.registers.iter().enumerate() {
+                let start = base.addr() + r.0;
+                let end = start + r.2.size() as u16;
+                index.push((start, end, module_idx, *base_idx, reg_idx));
+            }
+        }
+    }
+
+    writeln!(s, "    ]")?;
+
+    Ok(index)
+}
+
+fn output_modules(modules: &Modules) -> Result<String> {
+    let mut decls = String::new();
+    let mut s = String::new();
+
+    // Sorted by first base address, not collected into a map keyed on it:
+    // two modules that are mutually exclusive across device variants can
+    // legitimately share a first base address (the same physical page means
+    // something different depending on the part), and a map would silently
+    // drop one of them.
+    let mut sorted: Vec<String> = modules.keys().cloned().collect();
+    sorted.sort_by_key(|name| (modules[name].base[0].addr(), name.clone()));
+
+    // Validate and emit each register's field table once; every variant's
+    // module array below just references the result by name.
+    let mut field_names = HashMap::new();
+
+    for (name, module) in modules {
+        for r in &module.registers {
+            let width = r.2.size() * 8;
+            let fields = output_fields(&mut decls, name, &r.1, &r.2, width, &r.3)?;
+            field_names.insert((name.clone(), r.1.clone()), fields);
+        }
+    }
+
+    // The set of device variants named anywhere in the family, either on a
+    // module or on one of its bases.
+    let mut devices = BTreeSet::new();
+
+    for module in modules.values() {
+        devices.extend(module.devices.iter().cloned());
+
+        for base in &module.base {
+            if let Some(d) = base.devices() {
+                devices.extend(d.iter().cloned());
+            }
+        }
+    }
+
+    writeln!(
+        &mut decls,
+        "\n#[derive(Copy, Clone, Debug, PartialEq, Eq)]\npub enum Device {{"
+    )?;
+
+    for device in &devices {
+        writeln!(&mut decls, "    {},", ident(device))?;
+    }
+
+    writeln!(&mut decls, "}}")?;
+
+    writeln!(
+        &mut decls,
+        "\npub static ALL_DEVICES: &[Device] = &[{}];",
+        devices.iter().map(|d| format!("Device::{}", ident(d))).collect::<Vec<_>>().join(", ")
+    )?;
+
+    // Emitted as a named `static` rather than returned straight out of
+    // `modules()`'s body: once a `Register` literal below references a named
+    // field-table `static` (instead of a bare `&[]`), the compiler can no
+    // longer rvalue-promote the array literal, and a function returning it
+    // directly fails to borrow-check.
+    write!(&mut s, "\nstatic MODULES: &[Module<'static>] = ")?;
+
+    let mut index = output_module_array(&mut s, modules, &sorted, &field_names, |_, _| true)?;
+
+    writeln!(&mut s, ";")?;
+
+    writeln!(
+        &mut s,
+        "\npub fn modules() -> &'static [Module<'static>] {{\n    MODULES\n}}"
+    )?;
+
+    // `resolve()` binary searches `ADDRESS_INDEX` by start address, so it
+    // must come out sorted -- which `output_module_array` doesn't guarantee
+    // on its own, since module/base declaration order needn't be ascending
+    // (a module can legitimately declare a device-scoped base out of order
+    // relative to another module's). Sort it here, then assert the result is
+    // non-decreasing so a future change that breaks this invariant fails the
+    // build instead of silently mis-resolving addresses.
+    index.sort_by_key(|&(start, ..)| start);
+
+    for w in index.windows(2) {
+        if w[1].0 < w[0].0 {
+            bail!(
+                "ADDRESS_INDEX failed to sort: 0x{:x} before 0x{:x}",
+                w[0].0,
+                w[1].0
+            );
+        }
+    }
+
+    writeln!(
+        &mut decls,
+        "\npub static ADDRESS_INDEX: &[(u16, u16, usize, usize, usize)] = &["
+    )?;
+
+    for (start, end, module_idx, base_idx, reg_idx) in &index {
+        writeln!(
+            &mut decls,
+            "    (0x{:x}, 0x{:x}, {}, {}, {}),",
+            start, end, module_idx, base_idx, reg_idx
+        )?;
+    }
+
+    writeln!(&mut decls, "];")?;
+
+    for device in &devices {
+        write!(
+            &mut s,
+            "\nstatic MODULES_{}: &[Module<'static>] = ",
+            ident(device)
+        )?;
+
+        let device_sorted = sorted_for_device(modules, device);
+
+        output_module_array(&mut s, modules, &device_sorted, &field_names, |module, base| {
+            applies_to(&module.devices, device)
+                && applies_to(base.devices().unwrap_or(&module.devices), device)
+        })?;
+
+        writeln!(&mut s, ";")?;
+    }
+
+    writeln!(
+        &mut s,
+        "\npub fn modules_for(device: Device) -> &'static [Module<'static>] {{\n    match device {{"
+    )?;
+
+    for device in &devices {
+        writeln!(
+            &mut s,
+            "        Device::{} => MODULES_{},",
+            ident(device),
+            ident(device)
+        )?;
     }
 
-    writeln!(&mut s, "    ]\n}}")?;
+    writeln!(&mut s, "    }}\n}}")?;
 
-    Ok(s)
+    decls.push_str(&s);
+    Ok(decls)
 }
 
 fn codegen() -> Result<()> {
@@ -0,0 +1,414 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+//! A driver for talking to a ClockMatrix part over I2C.
+//!
+//! Registers live in a flat 16-bit address space, but the part only exposes
+//! an 8-bit offset on the wire: the high byte of the address is selected
+//! out-of-band by writing [`PAGE_ADDR_15_8`], per the "8A3xxxx Family
+//! Programming Guide".  [`blocking::ClockMatrix`] and [`asynch::ClockMatrix`]
+//! both wrap an `embedded-hal` I2C bus and take care of this page selection,
+//! caching the last page written so that a run of accesses within the same
+//! page costs a single I2C transaction rather than two.
+
+use crate::{offset, page, Contents, Module, Payload, Register, PAGE_ADDR_15_8};
+
+/// The largest `Contents::size()` across all register kinds (`TimeOfDay`),
+/// plus one byte for the register offset itself, sizing the scratch buffer
+/// used for a single register access.
+const MAX_TRANSACTION_LEN: usize = Contents::TimeOfDay.size() as usize + 1;
+
+/// Tracks the page last selected via [`PAGE_ADDR_15_8`], so that repeated
+/// accesses to registers on the same page don't re-select it.
+#[derive(Default)]
+struct PageCache(Option<u8>);
+
+impl PageCache {
+    /// Returns the page-select write needed to address `addr`, or `None` if
+    /// that page is already selected.
+    fn select(&mut self, addr: u16) -> Option<u8> {
+        let want = page(addr);
+
+        if self.0 == Some(want) {
+            None
+        } else {
+            self.0 = Some(want);
+            Some(want)
+        }
+    }
+}
+
+pub mod blocking {
+    use super::*;
+    use embedded_hal::i2c::I2c;
+
+    /// A ClockMatrix device on a blocking `embedded-hal` I2C bus.
+    pub struct ClockMatrix<I> {
+        i2c: I,
+        address: u8,
+        page: PageCache,
+        buf: [u8; MAX_TRANSACTION_LEN],
+    }
+
+    impl<I> ClockMatrix<I> {
+        /// Creates a new driver for the device at `address` on `i2c`.
+        pub fn new(i2c: I, address: u8) -> Self {
+            Self {
+                i2c,
+                address,
+                page: PageCache::default(),
+                buf: [0; MAX_TRANSACTION_LEN],
+            }
+        }
+
+        /// Releases the underlying I2C bus.
+        pub fn free(self) -> I {
+            self.i2c
+        }
+    }
+
+    impl<I: I2c> ClockMatrix<I> {
+        fn select_page(&mut self, addr: u16) -> Result<(), I::Error> {
+            if let Some(want) = self.page.select(addr) {
+                self.i2c.write(self.address, &[PAGE_ADDR_15_8, want])?;
+            }
+
+            Ok(())
+        }
+
+        /// Reads `register`, the `base_idx`'th instance of `module`,
+        /// selecting its page first if needed. `module`, `base_idx` and
+        /// `register` are typically the result of [`crate::resolve`] or
+        /// [`crate::resolve_for`].
+        pub fn read_register(
+            &mut self,
+            module: &Module<'_>,
+            base_idx: usize,
+            register: &Register<'_>,
+        ) -> Result<Payload<'_>, I::Error> {
+            let addr = module.base[base_idx] + register.offset;
+            self.select_page(addr)?;
+
+            let len = register.contents.size() as usize;
+
+            self.i2c.write_read(
+                self.address,
+                &[offset(addr)],
+                &mut self.buf[..len],
+            )?;
+
+            Ok(Payload::from_slice(register.contents, &mut self.buf[..len])
+                .expect("buffer sized for contents"))
+        }
+
+        /// Writes `value` to `register`, the `base_idx`'th instance of
+        /// `module`, selecting its page first if needed. `module`,
+        /// `base_idx` and `register` are typically the result of
+        /// [`crate::resolve`] or [`crate::resolve_for`].
+        pub fn write_register(
+            &mut self,
+            module: &Module<'_>,
+            base_idx: usize,
+            register: &Register<'_>,
+            value: u64,
+        ) -> Result<(), I::Error> {
+            let addr = module.base[base_idx] + register.offset;
+            self.select_page(addr)?;
+
+            let len = register.contents.size() as usize;
+            self.buf[0] = offset(addr);
+
+            Payload::into_slice(register.contents, value, &mut self.buf[1..len + 1])
+                .expect("buffer sized for contents");
+
+            self.i2c.write(self.address, &self.buf[..len + 1])
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use embedded_hal::i2c::{Error, ErrorKind, ErrorType, Operation};
+        extern crate std;
+        use std::vec::Vec;
+
+        #[derive(Debug)]
+        struct MockError;
+
+        impl Error for MockError {
+            fn kind(&self) -> ErrorKind {
+                ErrorKind::Other
+            }
+        }
+
+        /// A bare-bones I2C mock recording every byte written and replaying
+        /// `reads` in order for every byte read, just enough to exercise
+        /// page selection and addressing without a real bus.
+        #[derive(Default)]
+        struct MockI2c {
+            written: Vec<u8>,
+            reads: Vec<u8>,
+        }
+
+        impl ErrorType for MockI2c {
+            type Error = MockError;
+        }
+
+        impl I2c for MockI2c {
+            fn transaction(
+                &mut self,
+                _address: u8,
+                operations: &mut [Operation<'_>],
+            ) -> Result<(), Self::Error> {
+                for op in operations {
+                    match op {
+                        Operation::Write(bytes) => self.written.extend_from_slice(bytes),
+                        Operation::Read(buf) => {
+                            for b in buf.iter_mut() {
+                                *b = self.reads.remove(0);
+                            }
+                        }
+                    }
+                }
+
+                Ok(())
+            }
+        }
+
+        const REGISTER: Register<'static> = Register {
+            name: "TEST_REG",
+            offset: 0x10,
+            contents: Contents::Byte,
+            fields: &[],
+        };
+
+        const MODULE: Module<'static> = Module {
+            name: "TEST_MODULE",
+            base: &[0x0b00],
+            registers: &[],
+        };
+
+        #[test]
+        fn write_register_addresses_via_module_base() {
+            let mut cm = ClockMatrix::new(MockI2c::default(), 0x50);
+            cm.write_register(&MODULE, 0, &REGISTER, 0xab).unwrap();
+
+            assert_eq!(
+                cm.free().written,
+                std::vec![PAGE_ADDR_15_8, 0x0b, 0x10, 0xab]
+            );
+        }
+
+        #[test]
+        fn read_register_addresses_via_module_base() {
+            let mut i2c = MockI2c::default();
+            i2c.reads.push(0xcd);
+
+            let mut cm = ClockMatrix::new(i2c, 0x50);
+            let payload = cm.read_register(&MODULE, 0, &REGISTER).unwrap();
+
+            assert_eq!(payload.value(), 0xcd);
+            assert_eq!(cm.free().written, std::vec![PAGE_ADDR_15_8, 0x0b, 0x10]);
+        }
+
+        #[test]
+        fn same_page_is_selected_once() {
+            let mut cm = ClockMatrix::new(MockI2c::default(), 0x50);
+            let other = Register {
+                name: "OTHER_REG",
+                offset: 0x11,
+                contents: Contents::Byte,
+                fields: &[],
+            };
+
+            cm.write_register(&MODULE, 0, &REGISTER, 0xab).unwrap();
+            cm.write_register(&MODULE, 0, &other, 0xcd).unwrap();
+
+            assert_eq!(
+                cm.free().written,
+                std::vec![PAGE_ADDR_15_8, 0x0b, 0x10, 0xab, 0x11, 0xcd]
+            );
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub mod asynch {
+    use super::*;
+    use embedded_hal_async::i2c::I2c;
+
+    /// A ClockMatrix device on an async `embedded-hal-async` I2C bus.
+    pub struct ClockMatrix<I> {
+        i2c: I,
+        address: u8,
+        page: PageCache,
+        buf: [u8; MAX_TRANSACTION_LEN],
+    }
+
+    impl<I> ClockMatrix<I> {
+        /// Creates a new driver for the device at `address` on `i2c`.
+        pub fn new(i2c: I, address: u8) -> Self {
+            Self {
+                i2c,
+                address,
+                page: PageCache::default(),
+                buf: [0; MAX_TRANSACTION_LEN],
+            }
+        }
+
+        /// Releases the underlying I2C bus.
+        pub fn free(self) -> I {
+            self.i2c
+        }
+    }
+
+    impl<I: I2c> ClockMatrix<I> {
+        async fn select_page(&mut self, addr: u16) -> Result<(), I::Error> {
+            if let Some(want) = self.page.select(addr) {
+                self.i2c.write(self.address, &[PAGE_ADDR_15_8, want]).await?;
+            }
+
+            Ok(())
+        }
+
+        /// Reads `register`, the `base_idx`'th instance of `module`,
+        /// selecting its page first if needed. `module`, `base_idx` and
+        /// `register` are typically the result of [`crate::resolve`] or
+        /// [`crate::resolve_for`].
+        pub async fn read_register(
+            &mut self,
+            module: &Module<'_>,
+            base_idx: usize,
+            register: &Register<'_>,
+        ) -> Result<Payload<'_>, I::Error> {
+            let addr = module.base[base_idx] + register.offset;
+            self.select_page(addr).await?;
+
+            let len = register.contents.size() as usize;
+
+            self.i2c
+                .write_read(self.address, &[offset(addr)], &mut self.buf[..len])
+                .await?;
+
+            Ok(Payload::from_slice(register.contents, &mut self.buf[..len])
+                .expect("buffer sized for contents"))
+        }
+
+        /// Writes `value` to `register`, the `base_idx`'th instance of
+        /// `module`, selecting its page first if needed. `module`,
+        /// `base_idx` and `register` are typically the result of
+        /// [`crate::resolve`] or [`crate::resolve_for`].
+        pub async fn write_register(
+            &mut self,
+            module: &Module<'_>,
+            base_idx: usize,
+            register: &Register<'_>,
+            value: u64,
+        ) -> Result<(), I::Error> {
+            let addr = module.base[base_idx] + register.offset;
+            self.select_page(addr).await?;
+
+            let len = register.contents.size() as usize;
+            self.buf[0] = offset(addr);
+
+            Payload::into_slice(register.contents, value, &mut self.buf[1..len + 1])
+                .expect("buffer sized for contents");
+
+            self.i2c.write(self.address, &self.buf[..len + 1]).await
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use embedded_hal_async::i2c::{Error, ErrorKind, ErrorType, Operation};
+        extern crate std;
+        use std::vec::Vec;
+
+        #[derive(Debug)]
+        struct MockError;
+
+        impl Error for MockError {
+            fn kind(&self) -> ErrorKind {
+                ErrorKind::Other
+            }
+        }
+
+        /// A bare-bones I2C mock recording every byte written and replaying
+        /// `reads` in order for every byte read, just enough to exercise
+        /// page selection and addressing without a real bus.
+        #[derive(Default)]
+        struct MockI2c {
+            written: Vec<u8>,
+            reads: Vec<u8>,
+        }
+
+        impl ErrorType for MockI2c {
+            type Error = MockError;
+        }
+
+        impl I2c for MockI2c {
+            async fn transaction(
+                &mut self,
+                _address: u8,
+                operations: &mut [Operation<'_>],
+            ) -> Result<(), Self::Error> {
+                for op in operations {
+                    match op {
+                        Operation::Write(bytes) => self.written.extend_from_slice(bytes),
+                        Operation::Read(buf) => {
+                            for b in buf.iter_mut() {
+                                *b = self.reads.remove(0);
+                            }
+                        }
+                    }
+                }
+
+                Ok(())
+            }
+        }
+
+        const REGISTER: Register<'static> = Register {
+            name: "TEST_REG",
+            offset: 0x10,
+            contents: Contents::Byte,
+            fields: &[],
+        };
+
+        const MODULE: Module<'static> = Module {
+            name: "TEST_MODULE",
+            base: &[0x0b00],
+            registers: &[],
+        };
+
+        #[test]
+        fn write_register_addresses_via_module_base() {
+            futures::executor::block_on(async {
+                let mut cm = ClockMatrix::new(MockI2c::default(), 0x50);
+                cm.write_register(&MODULE, 0, &REGISTER, 0xab).await.unwrap();
+
+                assert_eq!(
+                    cm.free().written,
+                    std::vec![PAGE_ADDR_15_8, 0x0b, 0x10, 0xab]
+                );
+            });
+        }
+
+        #[test]
+        fn read_register_addresses_via_module_base() {
+            futures::executor::block_on(async {
+                let mut i2c = MockI2c::default();
+                i2c.reads.push(0xcd);
+
+                let mut cm = ClockMatrix::new(i2c, 0x50);
+                let payload = cm.read_register(&MODULE, 0, &REGISTER).await.unwrap();
+
+                assert_eq!(payload.value(), 0xcd);
+                assert_eq!(cm.free().written, std::vec![PAGE_ADDR_15_8, 0x0b, 0x10]);
+            });
+        }
+    }
+}
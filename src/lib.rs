@@ -16,6 +16,16 @@
 //! definitions themselves are contained in a RON file that, at build time
 //! via `build.rs`, is turned into the static definition.
 //!
+//! The [`driver`] module builds on these definitions with an `embedded-hal`
+//! I2C driver that handles page selection.
+//!
+//! The family spans parts with differing module complements; [`modules()`]
+//! is the union of every module the RON file knows about, while
+//! [`modules_for()`] filters that down to what a specific [`Device`]
+//! variant actually has.
+
+pub mod driver;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Contents {
     Byte,
@@ -48,7 +58,7 @@ pub fn offset(addr: u16) -> u8 {
 }
 
 impl Contents {
-    pub fn size(&self) -> u8 {
+    pub const fn size(&self) -> u8 {
         match self {
             Contents::Byte => 1,
             Contents::Word => 2,
@@ -65,11 +75,11 @@ impl Contents {
 #[derive(Debug, PartialEq)]
 pub struct Payload<'a> {
     pub contents: Contents,
-    pub data: &'a [u8],
+    pub data: &'a mut [u8],
 }
 
 impl<'a> Payload<'a> {
-    pub fn from_slice(contents: Contents, slice: &'a [u8]) -> Option<Self> {
+    pub fn from_slice(contents: Contents, slice: &'a mut [u8]) -> Option<Self> {
         let len = contents.size() as usize;
 
         if slice.len() < len {
@@ -77,7 +87,7 @@ impl<'a> Payload<'a> {
         } else {
             Some(Self {
                 contents: contents,
-                data: &slice[0..len],
+                data: &mut slice[0..len],
             })
         }
     }
@@ -103,6 +113,19 @@ impl<'a> Payload<'a> {
                 }
             }
 
+            Contents::TimeOfDay => {
+                // `value` carries only the seconds portion, as that's all
+                // `Payload::value()` decodes; zero the sub-second bytes.
+                // Use `Payload::tod_into_slice` to also set the nanoseconds.
+                for i in 0..5 {
+                    data[i] = 0;
+                }
+
+                for i in 5..11 {
+                    data[i] = ((value >> ((i - 5) * 8)) & 0xff) as u8;
+                }
+            }
+
             _ => {
                 for i in 0..len {
                     data[i] = ((value >> (i * 8)) & 0xff) as u8;
@@ -112,7 +135,35 @@ impl<'a> Payload<'a> {
 
         Some(Self {
             contents: contents,
-            data: &data[0..len],
+            data: &mut data[0..len],
+        })
+    }
+
+    /// Packs a `(seconds, nanoseconds)` pair into the 11-byte `TimeOfDay`
+    /// layout: the low 5 bytes hold the sub-second nanoseconds, and the
+    /// remaining 6 bytes the 48-bit seconds count since the PTP/Unix epoch.
+    pub fn tod_into_slice(
+        seconds: u64,
+        nanos: u32,
+        data: &'a mut [u8],
+    ) -> Option<Self> {
+        let len = Contents::TimeOfDay.size() as usize;
+
+        if data.len() < len {
+            return None;
+        }
+
+        for i in 0..5 {
+            data[i] = ((nanos as u64 >> (i * 8)) & 0xff) as u8;
+        }
+
+        for i in 5..11 {
+            data[i] = ((seconds >> ((i - 5) * 8)) & 0xff) as u8;
+        }
+
+        Some(Self {
+            contents: Contents::TimeOfDay,
+            data: &mut data[0..len],
         })
     }
 
@@ -163,6 +214,75 @@ impl<'a> Payload<'a> {
             }
         }
     }
+
+    /// Decodes a `TimeOfDay` payload into a `(seconds, nanoseconds)` pair:
+    /// `seconds` since the PTP epoch (which coincides with the Unix epoch),
+    /// as also returned by `value()`, plus the sub-second `nanoseconds` that
+    /// `value()` discards.
+    pub fn tod(&self) -> (u64, u32) {
+        let mut nanos = 0u64;
+
+        for i in 0..5 {
+            nanos |= (self.data[i] as u64) << (i * 8);
+        }
+
+        (self.value(), nanos as u32)
+    }
+
+    /// Extracts `field`'s bits out of this payload's value, right-shifted
+    /// down to bit 0.
+    pub fn field(&self, field: &Field) -> u64 {
+        (self.value() >> field.lsb) & field.mask()
+    }
+
+    /// Sets `field`'s bits to `value`, masking to the field's width, and
+    /// re-encodes the result into this payload's underlying bytes.
+    pub fn with_field(&mut self, field: &Field, value: u64) -> &mut Self {
+        let mask = field.mask();
+        let updated =
+            (self.value() & !(mask << field.lsb)) | ((value & mask) << field.lsb);
+
+        for i in 0..self.data.len() {
+            self.data[i] = ((updated >> (i * 8)) & 0xff) as u8;
+        }
+
+        self
+    }
+}
+
+/// A named, bit-addressed field within a [`Register`], such as a DPLL mode
+/// or output divider packed alongside other controls in the same register.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Field<'a> {
+    pub name: &'a str,
+    pub lsb: u8,
+    pub msb: u8,
+
+    /// Names for this field's enumerated values, if it has any (e.g. a mode
+    /// field whose value `2` means `"holdover"`); empty for a plain numeric
+    /// field.
+    pub values: &'a [(u64, &'a str)],
+}
+
+impl<'a> Field<'a> {
+    /// A mask covering this field's bits, right-justified to bit 0.
+    fn mask(&self) -> u64 {
+        let width = (self.msb - self.lsb + 1) as u32;
+
+        if width >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << width) - 1
+        }
+    }
+
+    /// The name of `value` among this field's enumerated values, if any.
+    pub fn name_for(&self, value: u64) -> Option<&'a str> {
+        self.values
+            .iter()
+            .find(|(v, _)| *v == value)
+            .map(|(_, name)| *name)
+    }
 }
 
 #[derive(Debug)]
@@ -170,6 +290,7 @@ pub struct Register<'a> {
     pub name: &'a str,
     pub offset: u16,
     pub contents: Contents,
+    pub fields: &'a [Field<'a>],
 }
 
 #[derive(Debug)]
@@ -181,6 +302,78 @@ pub struct Module<'a> {
 
 include!(concat!(env!("OUT_DIR"), "/modules.rs"));
 
+/// Resolves `addr` in the flat 16-bit register address space to the module,
+/// base index (which instance of the module, for modules with more than one
+/// `base`), and register that contains it, searching every module in the
+/// family regardless of device.
+///
+/// Backed by `ADDRESS_INDEX`, a `build.rs`-generated table sorted by
+/// address, so this is a binary search rather than a scan over every
+/// module. Note that unlike a single device's view, the family as a whole
+/// may reuse the same address for different modules across mutually
+/// exclusive variants; the ranges in `ADDRESS_INDEX` then overlap, and the
+/// binary search may return either module's register or step past both and
+/// return `None`. Prefer [`resolve_for`] when the device is known.
+pub fn resolve(addr: u16) -> Option<(&'static Module<'static>, usize, &'static Register<'static>)> {
+    let i = ADDRESS_INDEX
+        .binary_search_by(|&(start, end, ..)| {
+            if addr < start {
+                core::cmp::Ordering::Greater
+            } else if addr >= end {
+                core::cmp::Ordering::Less
+            } else {
+                core::cmp::Ordering::Equal
+            }
+        })
+        .ok()?;
+
+    let (_, _, module_idx, base_idx, reg_idx) = ADDRESS_INDEX[i];
+    let module = &modules()[module_idx];
+
+    Some((module, base_idx, &module.registers[reg_idx]))
+}
+
+/// Like [`resolve`], but only considers modules present on `device`, so it
+/// gives an unambiguous answer even where the family as a whole reuses an
+/// address across mutually exclusive variants.
+pub fn resolve_for(
+    device: Device,
+    addr: u16,
+) -> Option<(&'static Module<'static>, usize, &'static Register<'static>)> {
+    for module in modules_for(device) {
+        for (base_idx, base) in module.base.iter().enumerate() {
+            for register in module.registers {
+                let start = base + register.offset;
+                let end = start + register.contents.size() as u16;
+
+                if (start..end).contains(&addr) {
+                    return Some((module, base_idx, register));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Finds a register by name, searching every module in the family
+/// regardless of device. Prefer [`lookup_for`] when the device is known, as
+/// a name may not be unique across mutually exclusive variants.
+pub fn lookup(name: &str) -> Option<&'static Register<'static>> {
+    modules()
+        .iter()
+        .flat_map(|module| module.registers)
+        .find(|register| register.name == name)
+}
+
+/// Like [`lookup`], but only considers modules present on `device`.
+pub fn lookup_for(device: Device, name: &str) -> Option<&'static Register<'static>> {
+    modules_for(device)
+        .iter()
+        .flat_map(|module| module.registers)
+        .find(|register| register.name == name)
+}
+
 #[cfg(test)]
 mod tests {
     extern crate std;
@@ -194,33 +387,39 @@ mod tests {
         println!("{:#x?}", modules);
     }
 
+    // A variant that omits a page or a base instance can butt two modules'
+    // address ranges or register names up against each other in a way that
+    // would look like an overlap/duplicate in the combined `modules()` view,
+    // so these checks run once per device rather than against `modules()`.
     #[test]
     fn address_orthogonality() {
-        let modules = modules();
-        let mut seen = 0;
-
-        for module in modules {
-            for i in 0..module.base.len() {
-                let base = module.base[i];
-
-                let name = if module.base.len() > 1 {
-                    format!("{}_{}", module.name, i)
-                } else {
-                    format!("{}", module.name)
-                };
-
-                for register in module.registers {
-                    let addr = base + register.offset;
-                    let limit = addr + register.contents.size() as u16;
-                    assert!(addr >= seen);
-                    println!(
-                        "0x{:04x} - 0x{:04x}:  {}.{}",
-                        addr,
-                        limit - 1,
-                        name,
-                        register.name
-                    );
-                    seen = addr + register.contents.size() as u16;
+        for device in ALL_DEVICES {
+            let mut seen = 0;
+
+            for module in modules_for(*device) {
+                for i in 0..module.base.len() {
+                    let base = module.base[i];
+
+                    let name = if module.base.len() > 1 {
+                        format!("{}_{}", module.name, i)
+                    } else {
+                        format!("{}", module.name)
+                    };
+
+                    for register in module.registers {
+                        let addr = base + register.offset;
+                        let limit = addr + register.contents.size() as u16;
+                        assert!(addr >= seen);
+                        println!(
+                            "{:?} 0x{:04x} - 0x{:04x}:  {}.{}",
+                            device,
+                            addr,
+                            limit - 1,
+                            name,
+                            register.name
+                        );
+                        seen = addr + register.contents.size() as u16;
+                    }
                 }
             }
         }
@@ -228,16 +427,21 @@ mod tests {
 
     #[test]
     fn register_orthogonality() {
-        let mut regnames = HashSet::new();
-        let modules = modules();
+        for device in ALL_DEVICES {
+            let mut regnames = HashSet::new();
 
-        for module in modules {
-            for register in module.registers {
-                match regnames.insert(register.name) {
-                    false => {
-                        std::panic!("duplicate register {}", register.name);
+            for module in modules_for(*device) {
+                for register in module.registers {
+                    match regnames.insert(register.name) {
+                        false => {
+                            std::panic!(
+                                "duplicate register {} on {:?}",
+                                register.name,
+                                device
+                            );
+                        }
+                        true => {}
                     }
-                    true => {}
                 }
             }
         }
@@ -245,7 +449,7 @@ mod tests {
 
     #[test]
     fn data() {
-        let bytes = [0xde, 0x01, 0xce, 0xfa, 0xed, 0xfe];
+        let mut bytes = [0xde, 0x01, 0xce, 0xfa, 0xed, 0xfe];
 
         let check = [
             (Contents::Byte, 0xdeu64),
@@ -257,20 +461,109 @@ mod tests {
         ];
 
         for c in check {
-            let p = Payload::from_slice(c.0, &bytes).unwrap();
+            let p = Payload::from_slice(c.0, &mut bytes).unwrap();
             println!("{:x}", p.value());
             assert_eq!(p.value(), c.1);
         }
     }
 
+    #[test]
+    fn field() {
+        let mut bytes = [0b1010_0101u8];
+        let f = Field {
+            name: "test",
+            lsb: 2,
+            msb: 5,
+            values: &[],
+        };
+
+        let mut p = Payload::from_slice(Contents::Byte, &mut bytes).unwrap();
+        assert_eq!(p.field(&f), 0b1001);
+
+        p.with_field(&f, 0b0110);
+        assert_eq!(p.field(&f), 0b0110);
+        assert_eq!(p.value(), 0b1001_1001);
+    }
+
+    #[test]
+    fn field_name_for() {
+        let f = Field {
+            name: "mode",
+            lsb: 0,
+            msb: 2,
+            values: &[(0, "normal"), (2, "holdover")],
+        };
+
+        assert_eq!(f.name_for(0), Some("normal"));
+        assert_eq!(f.name_for(2), Some("holdover"));
+        assert_eq!(f.name_for(1), None);
+    }
+
     #[test]
     fn tod() {
         use chrono::NaiveDateTime;
 
-        let bytes = [0, 0, 0, 0, 0, 0x00, 0x77, 0x76, 0x5d, 0, 0];
-        let p = Payload::from_slice(Contents::TimeOfDay, &bytes).unwrap();
+        let mut bytes = [0x15, 0xcd, 0x5b, 0x07, 0x00, 0x00, 0x77, 0x76, 0x5d, 0, 0];
+        let p = Payload::from_slice(Contents::TimeOfDay, &mut bytes).unwrap();
 
         let d = NaiveDateTime::from_timestamp(p.value() as i64, 0);
         assert_eq!(format!("{}", d), "2019-09-09 16:00:00");
+        assert_eq!(p.tod(), (p.value(), 123_456_789));
+    }
+
+    #[test]
+    fn tod_into_slice() {
+        let mut bytes = [0u8; 11];
+        let p = Payload::tod_into_slice(0x5d767700, 123_456_789, &mut bytes).unwrap();
+        assert_eq!(p.tod(), (0x5d767700, 123_456_789));
+    }
+
+    #[test]
+    fn resolve_and_lookup() {
+        for module in modules() {
+            for (base_idx, base) in module.base.iter().enumerate() {
+                for register in module.registers {
+                    let addr = base + register.offset;
+                    let (found, found_base, found_register) =
+                        resolve(addr).expect("resolve should find its own register");
+
+                    assert_eq!(found.name, module.name);
+                    assert_eq!(found_base, base_idx);
+                    assert_eq!(found_register.name, register.name);
+                    assert_eq!(
+                        lookup(register.name).map(|r| r.name),
+                        Some(register.name)
+                    );
+                }
+            }
+        }
+
+        assert!(lookup("NOT_A_REAL_REGISTER").is_none());
+    }
+
+    #[test]
+    fn resolve_and_lookup_for_device() {
+        for device in ALL_DEVICES {
+            for module in modules_for(*device) {
+                for (base_idx, base) in module.base.iter().enumerate() {
+                    for register in module.registers {
+                        let addr = base + register.offset;
+                        let (found, found_base, found_register) =
+                            resolve_for(*device, addr)
+                                .expect("resolve_for should find its own register");
+
+                        assert_eq!(found.name, module.name);
+                        assert_eq!(found_base, base_idx);
+                        assert_eq!(found_register.name, register.name);
+                        assert_eq!(
+                            lookup_for(*device, register.name).map(|r| r.name),
+                            Some(register.name)
+                        );
+                    }
+                }
+            }
+
+            assert!(lookup_for(*device, "NOT_A_REAL_REGISTER").is_none());
+        }
     }
 }